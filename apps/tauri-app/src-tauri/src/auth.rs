@@ -0,0 +1,76 @@
+//! Loads (or generates) the shared secret CLI clients must present before the
+//! WebSocket bridge will register them or emit any events on their behalf.
+
+use rand::Rng;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const ENV_VAR: &str = "SMITHERS_AUTH_TOKEN";
+
+/// Returns the token from `SMITHERS_AUTH_TOKEN` if set, otherwise generates a
+/// fresh one for this run and writes it to a token file the CLI can read.
+pub fn load_or_generate_token() -> String {
+    if let Ok(token) = std::env::var(ENV_VAR) {
+        return token;
+    }
+
+    let token = generate_token();
+    let path = token_file_path();
+    match write_token_file(&path, &token) {
+        Ok(()) => println!("Wrote WebSocket auth token to {}", path.display()),
+        Err(e) => eprintln!("Failed to write auth token file {}: {}", path.display(), e),
+    }
+    token
+}
+
+/// Writes the token owner-only (0600 on Unix) so other local users on a
+/// shared host can't read it off disk and pass the auth gate themselves.
+///
+/// The path lives in the shared, world-writable temp directory under a fixed
+/// name, so an attacker could pre-create it (as a file they own, or a
+/// symlink into somewhere they control) before Smithers starts. `truncate`
+/// alone would silently reuse whatever is already there, keeping its
+/// existing owner/permissions and ignoring `mode(0o600)`. Retrying a few
+/// `create_new` (`O_EXCL`) attempts refuses to follow a symlink or reuse an
+/// existing file — it only ever writes through a descriptor this call
+/// itself created — while still tolerating a stale file left by our own
+/// previous run.
+fn write_token_file(path: &Path, token: &str) -> std::io::Result<()> {
+    const ATTEMPTS: u32 = 3;
+    let mut last_err = None;
+    for _ in 0..ATTEMPTS {
+        let _ = std::fs::remove_file(path);
+        match create_exclusive(path) {
+            Ok(mut file) => return file.write_all(token.as_bytes()),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap())
+}
+
+#[cfg(unix)]
+fn create_exclusive(path: &Path) -> std::io::Result<std::fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create_exclusive(path: &Path) -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+}
+
+fn generate_token() -> String {
+    let mut rng = rand::thread_rng();
+    (0..32).map(|_| format!("{:x}", rng.gen_range(0..16u8))).collect()
+}
+
+fn token_file_path() -> PathBuf {
+    std::env::temp_dir().join("smithers-ws-token")
+}
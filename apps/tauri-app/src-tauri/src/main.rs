@@ -1,27 +1,94 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod auth;
+mod pty_session;
+mod tls;
 mod ws_server;
 
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
-use tokio::sync::Mutex;
 use tauri::Manager;
+use tokio::sync::Mutex;
+use ws_server::{ConnectionHandle, ConnectionId, ConnectionInfo, HistoryEntry};
 
 pub struct AppState {
-    pub connected_clients: usize,
+    pub clients: HashMap<ConnectionId, ConnectionHandle>,
+    pub next_connection_id: ConnectionId,
+    pub total_messages_forwarded: u64,
+    pub history: VecDeque<HistoryEntry>,
 }
 
 impl AppState {
     pub fn new() -> Self {
         Self {
-            connected_clients: 0,
+            clients: HashMap::new(),
+            next_connection_id: 0,
+            total_messages_forwarded: 0,
+            history: VecDeque::new(),
         }
     }
 }
 
 #[tauri::command]
-fn get_connection_count(state: tauri::State<Arc<Mutex<AppState>>>) -> usize {
-    // Return current count (would need async for real impl)
-    0
+async fn get_connection_count(state: tauri::State<'_, Arc<Mutex<AppState>>>) -> Result<usize, String> {
+    let state = state.lock().await;
+    Ok(state.clients.len())
+}
+
+/// Snapshot of every currently connected CLI client, for the frontend's client list.
+#[tauri::command]
+async fn list_connections(
+    state: tauri::State<'_, Arc<Mutex<AppState>>>,
+) -> Result<Vec<ConnectionInfo>, String> {
+    let state = state.lock().await;
+    Ok(state
+        .clients
+        .iter()
+        .map(|(id, client)| client.info(*id))
+        .collect())
+}
+
+/// Push a single JSON payload to one connected CLI client.
+#[tauri::command]
+async fn send_to_client(
+    id: ConnectionId,
+    payload: serde_json::Value,
+    state: tauri::State<'_, Arc<Mutex<AppState>>>,
+) -> Result<(), String> {
+    let state = state.lock().await;
+    let client = state
+        .clients
+        .get(&id)
+        .ok_or_else(|| format!("no connected client with id {}", id))?;
+    client
+        .sender
+        .send(tokio_tungstenite::tungstenite::Message::Text(
+            payload.to_string(),
+        ))
+        .map_err(|e| format!("failed to queue message for client {}: {}", id, e))
+}
+
+/// Push a JSON payload to every connected CLI client.
+#[tauri::command]
+async fn broadcast(
+    payload: serde_json::Value,
+    state: tauri::State<'_, Arc<Mutex<AppState>>>,
+) -> Result<(), String> {
+    let state = state.lock().await;
+    let text = payload.to_string();
+    for client in state.clients.values() {
+        let _ = client
+            .sender
+            .send(tokio_tungstenite::tungstenite::Message::Text(text.clone()));
+    }
+    Ok(())
+}
+
+/// Drop all buffered history, e.g. once the frontend has consumed it.
+#[tauri::command]
+async fn clear_history(state: tauri::State<'_, Arc<Mutex<AppState>>>) -> Result<(), String> {
+    state.lock().await.history.clear();
+    Ok(())
 }
 
 fn main() {
@@ -36,12 +103,18 @@ fn main() {
 
             // Start WebSocket server in background
             tauri::async_runtime::spawn(async move {
-                ws_server::start_server(app_handle).await;
+                ws_server::start_server(app_handle, app_state).await;
             });
 
             Ok(())
         })
-        .invoke_handler(tauri::generate_handler![get_connection_count])
+        .invoke_handler(tauri::generate_handler![
+            get_connection_count,
+            list_connections,
+            send_to_client,
+            broadcast,
+            clear_history
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -0,0 +1,158 @@
+//! PTY bridging for the `{"type":"pty","shell":"..."}` WebSocket handshake.
+//!
+//! Once a session is live, stdio flows as binary frames on the same socket:
+//! the first byte of each `Message::Binary` frame is an opcode — `0` is raw
+//! bytes (stdin from the client, stdout/stderr to the client) and `1` is a
+//! JSON `{cols, rows}` resize request. There is no separate control channel.
+
+use portable_pty::{native_pty_system, Child, MasterPty, CommandBuilder, PtySize};
+use serde::Deserialize;
+use std::io::{Read, Write};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+
+mod opcode {
+    pub const DATA: u8 = 0;
+    pub const RESIZE: u8 = 1;
+}
+
+#[derive(Deserialize)]
+struct ResizePayload {
+    cols: u16,
+    rows: u16,
+}
+
+/// A shell running under a PTY, bridged onto a WebSocket connection's
+/// outbound sender. `shutdown` kills the child and waits for both pump
+/// threads to notice and exit — `spawn_blocking` tasks can't be cancelled by
+/// aborting their `JoinHandle`, so they have to be unblocked instead.
+pub struct PtySession {
+    inbound: mpsc::UnboundedSender<Vec<u8>>,
+    reader_task: JoinHandle<()>,
+    writer_task: JoinHandle<()>,
+    _child: Box<dyn Child + Send + Sync>,
+}
+
+impl PtySession {
+    pub fn spawn(shell: &str, outbound: mpsc::UnboundedSender<Message>) -> std::io::Result<Self> {
+        let pty_system = native_pty_system();
+        let pair = pty_system
+            .openpty(PtySize {
+                rows: 24,
+                cols: 80,
+                pixel_width: 0,
+                pixel_height: 0,
+            })
+            .map_err(to_io_error)?;
+
+        let child = pair
+            .slave
+            .spawn_command(CommandBuilder::new(shell))
+            .map_err(to_io_error)?;
+        drop(pair.slave);
+
+        let mut reader = pair.master.try_clone_reader().map_err(to_io_error)?;
+        let mut writer = pair.master.take_writer().map_err(to_io_error)?;
+        let master = pair.master;
+
+        // PTY output -> WebSocket, prefixed with the DATA opcode.
+        let reader_task = tokio::task::spawn_blocking(move || {
+            let mut buf = [0u8; 4096];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        let mut frame = Vec::with_capacity(n + 1);
+                        frame.push(opcode::DATA);
+                        frame.extend_from_slice(&buf[..n]);
+                        if outbound.send(Message::Binary(frame)).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        // WebSocket -> PTY input/resize, dispatched by the frame's opcode byte.
+        let (inbound_tx, mut inbound_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        let writer_task = tokio::task::spawn_blocking(move || {
+            while let Some(frame) = inbound_rx.blocking_recv() {
+                let Some((op, payload)) = frame.split_first() else {
+                    continue;
+                };
+                match *op {
+                    opcode::DATA => {
+                        if writer.write_all(payload).is_err() {
+                            break;
+                        }
+                    }
+                    opcode::RESIZE => {
+                        if let Ok(resize) = serde_json::from_slice::<ResizePayload>(payload) {
+                            let _ = master.resize(PtySize {
+                                rows: resize.rows,
+                                cols: resize.cols,
+                                pixel_width: 0,
+                                pixel_height: 0,
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        Ok(Self {
+            inbound: inbound_tx,
+            reader_task,
+            writer_task,
+            _child: child,
+        })
+    }
+
+    /// Feed one inbound binary frame (opcode byte + payload) to the PTY.
+    pub fn feed(&self, frame: Vec<u8>) {
+        let _ = self.inbound.send(frame);
+    }
+
+    /// Kill the child and wait for both pump threads to unblock and exit:
+    /// killing the child closes the PTY slave, which gives the reader thread
+    /// EOF, and dropping `inbound` makes the writer thread's
+    /// `blocking_recv()` return `None`.
+    pub async fn shutdown(mut self) {
+        let _ = self._child.kill();
+        let _ = self._child.wait();
+        drop(self.inbound);
+        let _ = self.reader_task.await;
+        let _ = self.writer_task.await;
+    }
+}
+
+fn to_io_error(e: anyhow::Error) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resize_payload_parses_cols_and_rows() {
+        let resize: ResizePayload = serde_json::from_slice(br#"{"cols":120,"rows":40}"#).unwrap();
+        assert_eq!(resize.cols, 120);
+        assert_eq!(resize.rows, 40);
+    }
+
+    #[test]
+    fn resize_payload_rejects_mistyped_or_missing_fields() {
+        assert!(serde_json::from_slice::<ResizePayload>(br#"{"cols":"nope"}"#).is_err());
+    }
+
+    #[test]
+    fn opcode_split_separates_tag_byte_from_payload() {
+        let frame = vec![opcode::DATA, b'h', b'i'];
+        let (op, payload) = frame.split_first().unwrap();
+        assert_eq!(*op, opcode::DATA);
+        assert_eq!(payload, b"hi");
+    }
+}
@@ -1,14 +1,94 @@
+use crate::pty_session::PtySession;
+use crate::tls::{self, MaybeTlsStream, TlsIdentity};
+use crate::AppState;
 use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
 use serde_json::Value;
+use subtle::ConstantTimeEq;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use tauri::{AppHandle, Emitter};
 use tokio::net::TcpListener;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::Duration;
 use tokio_tungstenite::accept_async;
+use tokio_tungstenite::tungstenite::Message;
+
+pub type ConnectionId = u64;
 
 const WS_PORT: u16 = 9876;
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+const STATS_INTERVAL: Duration = Duration::from_secs(10);
+const HISTORY_CAPACITY: usize = 200;
+
+/// One previously forwarded message, kept around so a client that attaches
+/// mid-stream (`{"type":"subscribe","replay":true}`) can catch up.
+#[derive(Clone, Serialize)]
+pub struct HistoryEntry {
+    pub timestamp: i64,
+    pub payload: Value,
+}
+
+/// Bind host/port and optional TLS identity, read from the environment so
+/// Smithers can bridge to remote CLIs or run behind stricter local policies
+/// instead of being limited to loopback plaintext.
+struct WsConfig {
+    host: String,
+    port: u16,
+    tls: Option<TlsIdentity>,
+}
+
+impl WsConfig {
+    fn from_env() -> Self {
+        let host = std::env::var("SMITHERS_WS_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+        let port = std::env::var("SMITHERS_WS_PORT")
+            .ok()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(WS_PORT);
+        let tls = match (
+            std::env::var("SMITHERS_TLS_CERT"),
+            std::env::var("SMITHERS_TLS_KEY"),
+        ) {
+            (Ok(cert_path), Ok(key_path)) => Some(TlsIdentity { cert_path, key_path }),
+            _ => None,
+        };
+        Self { host, port, tls }
+    }
+}
+
+/// Everything the registry needs to track and reach a connected client.
+pub struct ConnectionHandle {
+    pub sender: mpsc::UnboundedSender<Message>,
+    pub addr: SocketAddr,
+    pub connected_at: i64,
+    pub last_seen: i64,
+}
+
+impl ConnectionHandle {
+    pub fn info(&self, id: ConnectionId) -> ConnectionInfo {
+        ConnectionInfo {
+            id,
+            addr: self.addr.to_string(),
+            connected_at: self.connected_at,
+            last_seen: self.last_seen,
+        }
+    }
+}
 
-pub async fn start_server(app_handle: AppHandle) {
-    let addr = format!("127.0.0.1:{}", WS_PORT);
+/// Serializable snapshot of a `ConnectionHandle`, returned to the frontend.
+#[derive(Serialize, Clone)]
+pub struct ConnectionInfo {
+    pub id: ConnectionId,
+    pub addr: String,
+    pub connected_at: i64,
+    pub last_seen: i64,
+}
+
+pub async fn start_server(app_handle: AppHandle, state: Arc<Mutex<AppState>>) {
+    let config = WsConfig::from_env();
+    let addr = format!("{}:{}", config.host, config.port);
 
     let listener = match TcpListener::bind(&addr).await {
         Ok(l) => l,
@@ -18,18 +98,78 @@ pub async fn start_server(app_handle: AppHandle) {
         }
     };
 
-    println!("Smithers WebSocket server listening on ws://{}", addr);
+    let tls_acceptor = match &config.tls {
+        Some(identity) => match tls::build_acceptor(identity) {
+            Ok(acceptor) => Some(acceptor),
+            Err(e) => {
+                eprintln!("Failed to configure TLS, falling back to plaintext: {}", e);
+                None
+            }
+        },
+        None => None,
+    };
+    let scheme = if tls_acceptor.is_some() { "wss" } else { "ws" };
+
+    println!("Smithers WebSocket server listening on {}://{}", scheme, addr);
+
+    let auth_token = Arc::new(crate::auth::load_or_generate_token());
+    let started_at = chrono::Utc::now().timestamp_millis();
+    tokio::spawn(push_server_stats(app_handle.clone(), state.clone(), started_at));
 
     while let Ok((stream, addr)) = listener.accept().await {
         let app = app_handle.clone();
-        tokio::spawn(handle_connection(stream, addr, app));
+        let state = state.clone();
+        let auth_token = auth_token.clone();
+        let tls_acceptor = tls_acceptor.clone();
+        tokio::spawn(async move {
+            let stream = match tls_acceptor {
+                Some(acceptor) => match acceptor.accept(stream).await {
+                    Ok(tls_stream) => MaybeTlsStream::Tls(Box::new(tls_stream)),
+                    Err(e) => {
+                        eprintln!("TLS handshake failed for {}: {}", addr, e);
+                        return;
+                    }
+                },
+                None => MaybeTlsStream::Plain(stream),
+            };
+            handle_connection(stream, addr, app, state, auth_token, scheme).await;
+        });
+    }
+}
+
+/// Periodically emits `ws:server_stats` so the frontend can show connection
+/// health (uptime, client count, throughput) without polling.
+async fn push_server_stats(app_handle: AppHandle, state: Arc<Mutex<AppState>>, started_at: i64) {
+    let mut interval = tokio::time::interval(STATS_INTERVAL);
+    loop {
+        interval.tick().await;
+        let state = state.lock().await;
+        let now = chrono::Utc::now().timestamp_millis();
+        let stats = serde_json::json!({
+            "type": "server_stats",
+            "uptimeMs": now - started_at,
+            "connectedClients": state.clients.len(),
+            "totalMessagesForwarded": state.total_messages_forwarded,
+            "clients": state
+                .clients
+                .iter()
+                .map(|(id, client)| client.info(*id))
+                .collect::<Vec<_>>(),
+        });
+        drop(state);
+        if let Err(e) = app_handle.emit("ws:server_stats", stats) {
+            eprintln!("Failed to emit ws:server_stats event: {}", e);
+        }
     }
 }
 
 async fn handle_connection(
-    stream: tokio::net::TcpStream,
+    stream: MaybeTlsStream,
     addr: SocketAddr,
     app_handle: AppHandle,
+    state: Arc<Mutex<AppState>>,
+    auth_token: Arc<String>,
+    scheme: &'static str,
 ) {
     let ws_stream = match accept_async(stream).await {
         Ok(ws) => ws,
@@ -43,59 +183,296 @@ async fn handle_connection(
 
     let (mut write, mut read) = ws_stream.split();
 
+    // Require a valid auth message before trusting anything else from this
+    // client; other local processes must not be able to inject events.
+    match tokio::time::timeout(AUTH_TIMEOUT, read.next()).await {
+        Ok(Some(Ok(Message::Text(text)))) if is_valid_auth(&text, &auth_token) => {}
+        _ => {
+            eprintln!("Auth failed for {}", addr);
+            let _ = write
+                .send(Message::Text(
+                    serde_json::json!({"type": "auth_error"}).to_string(),
+                ))
+                .await;
+            let _ = write.send(Message::Close(None)).await;
+            return;
+        }
+    }
+
+    // Register this connection in the live registry so it shows up in get_connection_count
+    // and list_connections, and so the frontend can reply to or push messages into it.
+    let (outbound_tx, mut outbound_rx) = mpsc::unbounded_channel::<Message>();
+    let outbound_clone = outbound_tx.clone();
+    let connection_id = {
+        let mut state = state.lock().await;
+        let id = state.next_connection_id;
+        state.next_connection_id += 1;
+        let now = chrono::Utc::now().timestamp_millis();
+        state.clients.insert(
+            id,
+            ConnectionHandle {
+                sender: outbound_tx,
+                addr,
+                connected_at: now,
+                last_seen: now,
+            },
+        );
+        id
+    };
+    if let Err(e) = app_handle.emit("ws:client_connected", connection_id) {
+        eprintln!("Failed to emit ws:client_connected event: {}", e);
+    }
+
     // Send connected message
     let connected_msg = serde_json::json!({
         "type": "connected",
+        "connectionId": connection_id,
+        "scheme": format!("{}://", scheme),
         "serverVersion": env!("CARGO_PKG_VERSION"),
         "timestamp": chrono::Utc::now().timestamp_millis()
     });
 
-    if let Err(e) = write
-        .send(tokio_tungstenite::tungstenite::Message::Text(
-            connected_msg.to_string(),
-        ))
-        .await
-    {
+    if let Err(e) = write.send(Message::Text(connected_msg.to_string())).await {
         eprintln!("Failed to send connected message: {}", e);
+        disconnect(&state, &app_handle, connection_id).await;
         return;
     }
 
-    // Handle incoming messages from CLI
-    while let Some(msg) = read.next().await {
-        match msg {
-            Ok(tokio_tungstenite::tungstenite::Message::Text(text)) => {
-                // Parse JSON message
-                match serde_json::from_str::<Value>(&text) {
-                    Ok(json) => {
-                        // Forward message to frontend via Tauri event
-                        if let Some(msg_type) = json.get("type").and_then(|t| t.as_str()) {
-                            let event_name = format!("ws:{}", msg_type);
-                            if let Err(e) = app_handle.emit(&event_name, json.clone()) {
-                                eprintln!("Failed to emit event {}: {}", event_name, e);
-                            }
+    // Forward anything queued for this client (via send_to_client/broadcast) onto the socket.
+    let outbound_task = tokio::spawn(async move {
+        while let Some(msg) = outbound_rx.recv().await {
+            if write.send(msg).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // Set once a `{"type":"pty","shell":"..."}` handshake arrives; torn down on disconnect.
+    let mut pty: Option<PtySession> = None;
+
+    let mut ping_interval = tokio::time::interval(PING_INTERVAL);
+    ping_interval.tick().await; // first tick fires immediately; skip it
+
+    // Handle incoming messages from CLI, pinging periodically and pruning the
+    // connection if it goes quiet for longer than IDLE_TIMEOUT.
+    loop {
+        tokio::select! {
+            msg = read.next() => {
+                let Some(msg) = msg else { break };
+                match msg {
+                    Ok(Message::Text(text)) => {
+                        touch_last_seen(&state, connection_id).await;
+
+                        // Parse JSON message
+                        match serde_json::from_str::<Value>(&text) {
+                            Ok(json) => {
+                                if json.get("type").and_then(|t| t.as_str()) == Some("pty") {
+                                    // A client requesting a second PTY on one connection must not
+                                    // leak the first — tear it down before replacing it.
+                                    if let Some(old) = pty.take() {
+                                        old.shutdown().await;
+                                    }
+                                    let shell = json
+                                        .get("shell")
+                                        .and_then(|s| s.as_str())
+                                        .unwrap_or("/bin/sh");
+                                    match PtySession::spawn(shell, outbound_clone.clone()) {
+                                        Ok(session) => pty = Some(session),
+                                        Err(e) => eprintln!("Failed to spawn PTY for {}: {}", addr, e),
+                                    }
+                                    continue;
+                                }
+
+                                if json.get("type").and_then(|t| t.as_str()) == Some("subscribe") {
+                                    let replay = json
+                                        .get("replay")
+                                        .and_then(|r| r.as_bool())
+                                        .unwrap_or(false);
+                                    if replay {
+                                        let history = state.lock().await.history.clone();
+                                        for entry in history {
+                                            let _ = outbound_clone.send(Message::Text(
+                                                serde_json::json!({
+                                                    "type": "replay",
+                                                    "timestamp": entry.timestamp,
+                                                    "payload": entry.payload,
+                                                })
+                                                .to_string(),
+                                            ));
+                                        }
+                                    }
+                                    continue;
+                                }
 
-                            // Also emit to a general channel
-                            if let Err(e) = app_handle.emit("ws:message", json) {
-                                eprintln!("Failed to emit ws:message event: {}", e);
+                                // Forward message to frontend via Tauri event
+                                if let Some(msg_type) = json.get("type").and_then(|t| t.as_str()) {
+                                    let event_name = format!("ws:{}", msg_type);
+                                    if let Err(e) = app_handle.emit(&event_name, json.clone()) {
+                                        eprintln!("Failed to emit event {}: {}", event_name, e);
+                                    }
+
+                                    // Also emit to a general channel
+                                    if let Err(e) = app_handle.emit("ws:message", json.clone()) {
+                                        eprintln!("Failed to emit ws:message event: {}", e);
+                                    }
+
+                                    let mut state = state.lock().await;
+                                    state.total_messages_forwarded += 1;
+                                    push_history(
+                                        &mut state.history,
+                                        HistoryEntry {
+                                            timestamp: chrono::Utc::now().timestamp_millis(),
+                                            payload: json,
+                                        },
+                                    );
+                                }
+                            }
+                            Err(e) => {
+                                eprintln!("Failed to parse WebSocket message: {}", e);
                             }
                         }
                     }
+                    Ok(Message::Binary(data)) => {
+                        touch_last_seen(&state, connection_id).await;
+                        if let Some(session) = &pty {
+                            session.feed(data);
+                        }
+                    }
+                    Ok(Message::Pong(_)) => {
+                        touch_last_seen(&state, connection_id).await;
+                    }
+                    Ok(Message::Close(_)) => {
+                        println!("WebSocket connection closed by {}", addr);
+                        break;
+                    }
                     Err(e) => {
-                        eprintln!("Failed to parse WebSocket message: {}", e);
+                        eprintln!("WebSocket error from {}: {}", addr, e);
+                        break;
                     }
+                    _ => {}
                 }
             }
-            Ok(tokio_tungstenite::tungstenite::Message::Close(_)) => {
-                println!("WebSocket connection closed by {}", addr);
-                break;
-            }
-            Err(e) => {
-                eprintln!("WebSocket error from {}: {}", addr, e);
-                break;
+            _ = ping_interval.tick() => {
+                let last_seen = state
+                    .lock()
+                    .await
+                    .clients
+                    .get(&connection_id)
+                    .map(|c| c.last_seen);
+                let Some(last_seen) = last_seen else { break };
+                let idle_for = chrono::Utc::now().timestamp_millis() - last_seen;
+                if idle_for > IDLE_TIMEOUT.as_millis() as i64 {
+                    println!("Closing idle connection from {} after {}ms", addr, idle_for);
+                    break;
+                }
+                if outbound_clone.send(Message::Ping(Vec::new())).is_err() {
+                    break;
+                }
             }
-            _ => {}
         }
     }
 
+    if let Some(session) = pty.take() {
+        session.shutdown().await;
+    }
+    outbound_task.abort();
+    disconnect(&state, &app_handle, connection_id).await;
     println!("WebSocket connection ended for {}", addr);
 }
+
+fn is_valid_auth(text: &str, expected_token: &str) -> bool {
+    let Ok(json) = serde_json::from_str::<Value>(text) else {
+        return false;
+    };
+    if json.get("type").and_then(|t| t.as_str()) != Some("auth") {
+        return false;
+    }
+    let Some(token) = json.get("token").and_then(|t| t.as_str()) else {
+        return false;
+    };
+    // The gate's security rests on this one comparison, so it must not leak
+    // timing information about how many leading bytes of the token matched.
+    token.as_bytes().ct_eq(expected_token.as_bytes()).into()
+}
+
+async fn touch_last_seen(state: &Arc<Mutex<AppState>>, id: ConnectionId) {
+    if let Some(client) = state.lock().await.clients.get_mut(&id) {
+        client.last_seen = chrono::Utc::now().timestamp_millis();
+    }
+}
+
+async fn disconnect(state: &Arc<Mutex<AppState>>, app_handle: &AppHandle, id: ConnectionId) {
+    state.lock().await.clients.remove(&id);
+    if let Err(e) = app_handle.emit("ws:client_disconnected", id) {
+        eprintln!("Failed to emit ws:client_disconnected event: {}", e);
+    }
+}
+
+/// Append `entry` to the bounded history ring buffer, evicting the oldest
+/// entry once it's at `HISTORY_CAPACITY`.
+fn push_history(history: &mut std::collections::VecDeque<HistoryEntry>, entry: HistoryEntry) {
+    if history.len() >= HISTORY_CAPACITY {
+        history.pop_front();
+    }
+    history.push_back(entry);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn auth_accepts_matching_token() {
+        let msg = serde_json::json!({"type": "auth", "token": "secret"}).to_string();
+        assert!(is_valid_auth(&msg, "secret"));
+    }
+
+    #[test]
+    fn auth_rejects_wrong_token() {
+        let msg = serde_json::json!({"type": "auth", "token": "wrong"}).to_string();
+        assert!(!is_valid_auth(&msg, "secret"));
+    }
+
+    #[test]
+    fn auth_rejects_wrong_message_type() {
+        let msg = serde_json::json!({"type": "hello", "token": "secret"}).to_string();
+        assert!(!is_valid_auth(&msg, "secret"));
+    }
+
+    #[test]
+    fn auth_rejects_malformed_json() {
+        assert!(!is_valid_auth("not json", "secret"));
+    }
+
+    #[test]
+    fn history_evicts_oldest_entry_once_full() {
+        let mut history: VecDeque<HistoryEntry> = VecDeque::new();
+        for i in 0..HISTORY_CAPACITY {
+            push_history(
+                &mut history,
+                HistoryEntry {
+                    timestamp: i as i64,
+                    payload: serde_json::json!({ "i": i }),
+                },
+            );
+        }
+        assert_eq!(history.len(), HISTORY_CAPACITY);
+        assert_eq!(history.front().unwrap().timestamp, 0);
+
+        push_history(
+            &mut history,
+            HistoryEntry {
+                timestamp: HISTORY_CAPACITY as i64,
+                payload: serde_json::json!({ "i": HISTORY_CAPACITY }),
+            },
+        );
+
+        assert_eq!(history.len(), HISTORY_CAPACITY);
+        assert_eq!(history.front().unwrap().timestamp, 1);
+        assert_eq!(
+            history.back().unwrap().timestamp,
+            HISTORY_CAPACITY as i64
+        );
+    }
+}